@@ -1,61 +1,96 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 
 use crate::interval_stat_deque::{IntervalStatDeque, StatType};
+use crate::ring_buffer::RingBuffer;
 
 #[derive(Clone, Debug)]
 pub struct IntervalStats {
     pub min: f32,
     pub max: f32,
     pub sum: f32,
-    pub sum_squares: f32,
+    // Running mean and M2 (sum of squared deviations) instead of sum_squares,
+    // so variance is stable under the sliding window and over huge counts.
+    // Kept in f64 (like NodeData) so the accumulators do not drift over 10^8
+    // near-equal prices.
+    pub mean: f64,
+    pub m2: f64,
     pub count: usize,
     pub last: f32,
 }
 
+// Upper bound on a single decade's window, so the eagerly-allocated ring
+// buffer stays bounded (≈40 MB of f32 at the cap) instead of reserving hundreds
+// of MB for the largest decades on task creation.
+const MAX_INTERVAL_CAPACITY: usize = 10_usize.pow(7);
+
 pub struct IntervalStatsStore {
-    pub data: VecDeque<f32>,
+    pub data: RingBuffer,
     pub interval: usize,
     pub deque_min: IntervalStatDeque,
     pub deque_max: IntervalStatDeque,
     pub sum: f32,
-    pub sum_squares: f32,
+    pub mean: f64,
+    pub m2: f64,
     pub last: f32,
 }
 
 impl IntervalStatsStore {
     fn new(interval: usize) -> Self {
+        // Cap the reserved window so a large decade cannot eagerly allocate an
+        // unbounded buffer.
+        let interval = interval.min(MAX_INTERVAL_CAPACITY);
         IntervalStatsStore {
-            data: VecDeque::new(),
+            data: RingBuffer::with_capacity(interval),
             interval,
             deque_min: IntervalStatDeque::new(interval, StatType::Min),
             deque_max: IntervalStatDeque::new(interval, StatType::Max),
             sum: 0.0,
-            sum_squares: 0.0,
+            mean: 0.0,
+            m2: 0.0,
             last: 0.0,
         }
     }
 
     fn add(&mut self, value: f32) {
-        // adding new data
-        self.data.push_back(value);
+        // adding new data; a full buffer overwrites and returns the oldest value
+        let evicted = self.data.push(value);
         // basic stats
         self.sum += value;
-        self.sum_squares += value * value;
         self.last = value;
 
         // handling min and max
         self.deque_min.push(value);
         self.deque_max.push(value);
 
-        // handling to big window
-        if self.data.len() > self.interval {
-            if let Some(to_remove) = self.data.pop_front() {
-                self.sum -= to_remove;
-                self.sum_squares -= to_remove * to_remove;
+        // Welford online update of mean/M2 with the freshly pushed value. When
+        // the buffer was already full the eviction has not been folded out yet,
+        // so the transient count is one above the buffer length.
+        let value = value as f64;
+        let n = (self.data.len() + evicted.is_some() as usize) as f64;
+        let delta = value - self.mean;
+        self.mean += delta / n;
+        let delta2 = value - self.mean;
+        self.m2 += delta * delta2;
+
+        // handling to big window: the ring buffer already evicted the oldest
+        // value, so just fold it out of the running accumulators.
+        if let Some(to_remove) = evicted {
+            self.sum -= to_remove;
+
+            // Welford reverse update: drop the evicted value from mean/M2.
+            if self.data.is_empty() {
+                self.mean = 0.0;
+                self.m2 = 0.0;
+            } else {
+                let to_remove = to_remove as f64;
+                let n = self.data.len() as f64;
+                let delta = to_remove - self.mean;
+                self.mean -= delta / n;
+                self.m2 -= delta * (to_remove - self.mean);
             }
         }
-
-        println!("Count {}", self.data.len());
     }
 
     pub fn get_stats(&self) -> IntervalStats {
@@ -63,13 +98,29 @@ impl IntervalStatsStore {
             min: self.deque_min.stat(),
             max: self.deque_max.stat(),
             sum: self.sum,
-            sum_squares: self.sum_squares,
+            mean: self.mean,
+            m2: self.m2,
             count: self.data.len(),
             last: self.last,
         }
     }
 }
 
+// IntervalSnapshot is the compact persisted form of a single interval store:
+// the decade exponent plus the live window contents, from which the running
+// accumulators and min/max deques are rebuilt on recovery.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IntervalSnapshot {
+    pub key: usize,
+    pub values: Vec<f32>,
+}
+
+// StoreSnapshot is the persisted form of a whole SymbolDataStore.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StoreSnapshot {
+    pub intervals: Vec<IntervalSnapshot>,
+}
+
 pub struct SymbolDataStore {
     intervals: HashMap<usize, IntervalStatsStore>,
 }
@@ -95,12 +146,54 @@ impl SymbolDataStore {
     pub fn get_stats(&self, k: usize) -> Option<IntervalStats> {
         self.intervals.get(&k).map(|stats| stats.get_stats())
     }
+
+    // Capture the compact accumulators (just the live window per interval) so a
+    // recovering task can restore without replaying the whole price history.
+    pub fn snapshot(&self) -> StoreSnapshot {
+        let intervals = self
+            .intervals
+            .iter()
+            .map(|(&key, stats)| IntervalSnapshot {
+                key,
+                values: stats.data.to_vec(),
+            })
+            .collect();
+
+        StoreSnapshot { intervals }
+    }
+
+    // Rebuild a store from a snapshot by replaying each interval's retained
+    // window back through `add`, which reconstructs the running accumulators.
+    pub fn restore(snapshot: StoreSnapshot) -> Self {
+        let mut intervals = HashMap::new();
+        for snap in snapshot.intervals {
+            let mut stats = IntervalStatsStore::new(10_usize.pow(snap.key as u32));
+            for value in snap.values {
+                stats.add(value);
+            }
+            intervals.insert(snap.key, stats);
+        }
+
+        SymbolDataStore { intervals }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // The f64 Welford accumulators converge to the true mean/M2 but are not
+    // bit-exact after incremental add/remove, so compare with a small tolerance.
+    fn approx_eq(actual: f64, expected: f64) {
+        let tol = 1e-6 * (1.0 + expected.abs());
+        assert!(
+            (actual - expected).abs() < tol,
+            "Expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
     #[test]
     fn test_add_batch() {
         let k = 8_usize;
@@ -117,7 +210,8 @@ mod tests {
             assert_eq!(stats.min, 1.0);
             assert_eq!(stats.max, 1.0);
             assert_eq!(stats.sum, count as f32);
-            assert_eq!(stats.sum_squares, count as f32);
+            approx_eq(stats.mean, 1.0);
+            approx_eq(stats.m2, 0.0);
             assert_eq!(stats.last, 1.0);
         });
 
@@ -129,7 +223,8 @@ mod tests {
         assert_eq!(stats.min, 2.0);
         assert_eq!(stats.max, 2.0);
         assert_eq!(stats.sum, 20000.0);
-        assert_eq!(stats.sum_squares, 40000.0);
+        approx_eq(stats.mean, 2.0);
+        approx_eq(stats.m2, 0.0);
         assert_eq!(stats.last, 2.0);
 
         let stats = store.get_stats(3).unwrap();
@@ -137,7 +232,8 @@ mod tests {
         assert_eq!(stats.min, 2.0);
         assert_eq!(stats.max, 2.0);
         assert_eq!(stats.sum, 2000.0);
-        assert_eq!(stats.sum_squares, 4000.0);
+        approx_eq(stats.mean, 2.0);
+        approx_eq(stats.m2, 0.0);
         assert_eq!(stats.last, 2.0);
 
         let stats = store.get_stats(5).unwrap();
@@ -145,7 +241,8 @@ mod tests {
         assert_eq!(stats.min, 1.0);
         assert_eq!(stats.max, 2.0);
         assert_eq!(stats.sum, 30000.0);
-        assert_eq!(stats.sum_squares, 50000.0);
+        approx_eq(stats.mean, 1.5);
+        approx_eq(stats.m2, 5000.0);
         assert_eq!(stats.last, 2.0);
     }
 
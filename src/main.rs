@@ -3,18 +3,24 @@ use axum::{
     Router,
 };
 use manager::SymbolManager;
+use persistence::PersistenceConfig;
 use web::RouterHandle;
 
 mod datastore;
 mod interval_stat_deque;
 mod manager;
+mod persistence;
+mod ring_buffer;
+mod segment;
 mod web;
 
 #[tokio::main]
 async fn main() {
-    let symbols_manager = SymbolManager::new();
+    let symbols_manager = SymbolManager::new(PersistenceConfig::default());
     let add_batch_router = RouterHandle::new(symbols_manager.manager_tx.clone());
     let stats_router = RouterHandle::new(symbols_manager.manager_tx.clone());
+    let range_stats_router = RouterHandle::new(symbols_manager.manager_tx.clone());
+    let percentile_router = RouterHandle::new(symbols_manager.manager_tx.clone());
 
     tokio::spawn(symbols_manager.run());
 
@@ -23,7 +29,15 @@ async fn main() {
             "/add_batch",
             post(move |req| add_batch_router.handle_add_batch(req)),
         )
-        .route("/stats", get(move |req| stats_router.handle_get_stats(req)));
+        .route("/stats", get(move |req| stats_router.handle_get_stats(req)))
+        .route(
+            "/range_stats",
+            get(move |req| range_stats_router.handle_get_range_stats(req)),
+        )
+        .route(
+            "/percentile",
+            get(move |req| percentile_router.handle_get_percentile(req)),
+        );
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
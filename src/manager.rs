@@ -1,9 +1,12 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use serde::Serialize;
 use tokio::sync::mpsc;
 
 use crate::datastore::SymbolDataStore;
+use crate::persistence::{self, PersistenceConfig, RecoveredState, SymbolPersistence};
+use crate::segment::{MergeSortTree, NodeData, SegmentTree};
 
 // ManagerCommand is an enum that represents the commands that can be sent to the manager.
 pub enum ManagerCommand {
@@ -15,6 +18,17 @@ pub enum ManagerCommand {
         k: u32,
         resp: mpsc::Sender<Stats>,
     },
+    GetRangeStats {
+        start: usize,
+        end: usize,
+        resp: mpsc::Sender<RangeStats>,
+    },
+    GetPercentile {
+        start: usize,
+        end: usize,
+        q: f64,
+        resp: mpsc::Sender<Percentile>,
+    },
 }
 
 // State represents the statistics of a symbol.
@@ -24,7 +38,32 @@ pub struct Stats {
     pub max: f32,
     pub last: f32,
     pub avg: f32,
-    pub var: f32,
+    pub var_pop: f32,
+    pub var_sample: f32,
+    pub std_pop: f32,
+    pub std_sample: f32,
+}
+
+// RangeStats represents the aggregate over an arbitrary half-open index range
+// answered by the per-symbol segment tree.
+#[derive(Serialize)]
+pub struct RangeStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub var: f64,
+    pub sum: f64,
+    pub count: i64,
+    pub last: f64,
+}
+
+// Percentile is the q-th quantile of prices over an index range, answered by a
+// merge-sort tree built over the symbol's price history.
+#[derive(Serialize)]
+pub struct Percentile {
+    pub q: f64,
+    pub value: f64,
+    pub count: usize,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone)]
@@ -35,24 +74,39 @@ pub struct SymbolManager {
     pub manager_tx: mpsc::Sender<(Symbol, ManagerCommand)>,
     manager_rx: mpsc::Receiver<(Symbol, ManagerCommand)>,
     symbol_tasks: HashMap<Symbol, mpsc::Sender<ManagerCommand>>,
+    config: PersistenceConfig,
 }
 
 impl SymbolManager {
-    pub fn new() -> Self {
+    pub fn new(config: PersistenceConfig) -> Self {
         let (manager_tx, manager_rx) = mpsc::channel(100);
         SymbolManager {
             manager_tx,
             manager_rx,
             symbol_tasks: HashMap::new(),
+            config,
         }
     }
 
     pub async fn run(mut self) {
+        // Restore every persisted symbol before serving any request so that a
+        // query never races a half-recovered store.
+        self.recover_persisted_symbols();
+
         while let Some((symbol, command)) = self.manager_rx.recv().await {
             if !self.symbol_tasks.contains_key(&symbol) {
-                let (task_tx, task_rx) = mpsc::channel(100);
-                self.symbol_tasks.insert(symbol.clone(), task_tx.clone());
-                tokio::spawn(SymbolTask::new(task_rx).run());
+                match self.spawn_task(&symbol, None) {
+                    Ok(task_tx) => {
+                        self.symbol_tasks.insert(symbol.clone(), task_tx);
+                    }
+                    Err(err) => {
+                        // A bad symbol name (or unusable WAL) must not take the
+                        // manager down; drop the command so the caller sees the
+                        // handler's fallback error response.
+                        eprintln!("Rejecting commands for symbol {}: {err}", symbol.0);
+                        continue;
+                    }
+                }
             }
 
             if let Some(task_tx) = self.symbol_tasks.get(&symbol) {
@@ -60,17 +114,165 @@ impl SymbolManager {
             }
         }
     }
+
+    // Enumerate persisted symbols and spawn a pre-populated task for each.
+    fn recover_persisted_symbols(&mut self) {
+        let symbols = match persistence::list_symbols(&self.config) {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                eprintln!("Failed to list persisted symbols: {err}");
+                return;
+            }
+        };
+
+        for name in symbols {
+            match persistence::recover(&self.config, &name) {
+                Ok(recovered) => {
+                    let symbol = Symbol(name.clone());
+                    match self.spawn_task(&symbol, Some(recovered)) {
+                        Ok(task_tx) => {
+                            self.symbol_tasks.insert(symbol, task_tx);
+                        }
+                        Err(err) => eprintln!("Failed to restore symbol {name}: {err}"),
+                    }
+                }
+                Err(err) => eprintln!("Failed to recover symbol {name}: {err}"),
+            }
+        }
+    }
+
+    // Open the symbol's WAL and spawn its task, optionally restoring from a
+    // recovered snapshot plus WAL tail.
+    fn spawn_task(
+        &self,
+        symbol: &Symbol,
+        recovered: Option<RecoveredState>,
+    ) -> std::io::Result<mpsc::Sender<ManagerCommand>> {
+        let next_seq = recovered
+            .as_ref()
+            .and_then(|r| r.records.last())
+            .map(|record| record.seq + 1)
+            .unwrap_or(0);
+
+        // Opening validates the symbol name and the WAL; propagate failures
+        // rather than panicking the manager.
+        let persistence = SymbolPersistence::open(&self.config, &symbol.0, next_seq)?;
+
+        let (task_tx, task_rx) = mpsc::channel(100);
+        let snapshot_interval = self.config.snapshot_interval;
+        let task = match recovered {
+            Some(recovered) => {
+                SymbolTask::recovered(task_rx, persistence, snapshot_interval, recovered)
+            }
+            None => SymbolTask::new(task_rx, persistence, snapshot_interval),
+        };
+        tokio::spawn(task.run());
+
+        Ok(task_tx)
+    }
 }
 
-struct SymbolTask {
+// GuardedStore pairs the compact per-decade store with the highest batch
+// sequence it reflects, so the snapshot writer can read both atomically and
+// tag the snapshot with the exact sequence its window covers.
+struct GuardedStore {
     store: SymbolDataStore,
+    applied_seq: u64,
+}
+
+struct SymbolTask {
+    // Shared so the periodic snapshot can copy the live window on a blocking
+    // thread while the ingest task keeps accepting batches; the request path
+    // only ever holds the lock for the cheap add/read itself.
+    store: Arc<Mutex<GuardedStore>>,
+    // Full-history segment tree over every ingested price, backing ad-hoc
+    // index-range queries the per-decade stores cannot answer.
+    tree: SegmentTree<NodeData>,
+    // Retained price history, from which an immutable merge-sort tree is built
+    // on demand to answer percentile queries.
+    prices: Vec<f64>,
+    // Cached merge-sort tree over `prices` and the length it was built for, so
+    // repeated percentile queries do not rebuild the O(n log n) structure when
+    // no new prices have arrived.
+    percentile_tree: Option<MergeSortTree>,
+    percentile_tree_len: usize,
+    // Durable write-ahead log and snapshot writer for this symbol.
+    persistence: SymbolPersistence,
+    // Number of accepted batches between compact snapshots, and the count since
+    // the last one was flushed.
+    snapshot_interval: u64,
+    batches_since_snapshot: u64,
     receiver: mpsc::Receiver<ManagerCommand>,
 }
 
 impl SymbolTask {
-    fn new(receiver: mpsc::Receiver<ManagerCommand>) -> Self {
+    fn new(
+        receiver: mpsc::Receiver<ManagerCommand>,
+        persistence: SymbolPersistence,
+        snapshot_interval: u64,
+    ) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(GuardedStore {
+                store: SymbolDataStore::new(8),
+                applied_seq: 0,
+            })),
+            tree: SegmentTree::new(),
+            prices: Vec::new(),
+            percentile_tree: None,
+            percentile_tree_len: 0,
+            persistence,
+            snapshot_interval,
+            batches_since_snapshot: 0,
+            receiver,
+        }
+    }
+
+    // Rebuild a task from its latest snapshot plus any WAL tail. The per-decade
+    // store is restored from the compact snapshot and only the tail is replayed
+    // into it, while the full-history analytics (segment tree and price vector)
+    // are reconstructed from the complete WAL.
+    fn recovered(
+        receiver: mpsc::Receiver<ManagerCommand>,
+        persistence: SymbolPersistence,
+        snapshot_interval: u64,
+        recovered: RecoveredState,
+    ) -> Self {
+        let RecoveredState { snapshot, records } = recovered;
+
+        let (mut store, snapshot_seq) = match snapshot {
+            Some(snapshot) => (SymbolDataStore::restore(snapshot.store), Some(snapshot.seq)),
+            None => (SymbolDataStore::new(8), None),
+        };
+
+        // Track the highest sequence the compact store reflects, so a later
+        // snapshot is tagged correctly even before any new batch arrives.
+        let mut applied_seq = snapshot_seq.unwrap_or(0);
+
+        let mut tree = SegmentTree::new();
+        let mut prices = Vec::new();
+
+        for record in &records {
+            let leaves: Vec<f64> = record.values.iter().map(|&v| v as f64).collect();
+            tree.add_batch(&leaves);
+            prices.extend_from_slice(&leaves);
+
+            // The snapshot already reflects every batch up to `snapshot_seq`;
+            // only replay the tail into the compact store.
+            if snapshot_seq.is_none_or(|seq| record.seq > seq) {
+                store.add_batch(record.values.as_slice());
+            }
+            applied_seq = record.seq;
+        }
+
         Self {
-            store: SymbolDataStore::new(10usize.pow(8)),
+            store: Arc::new(Mutex::new(GuardedStore { store, applied_seq })),
+            tree,
+            prices,
+            percentile_tree: None,
+            percentile_tree_len: 0,
+            persistence,
+            snapshot_interval,
+            batches_since_snapshot: 0,
             receiver,
         }
     }
@@ -84,24 +286,79 @@ impl SymbolTask {
                         continue;
                     }
 
-                    self.store.add_batch(values.as_slice());
+                    // Durably log the batch before acknowledging so an accepted
+                    // batch survives a restart.
+                    let seq = match self.persistence.append_batch(values.as_slice()) {
+                        Ok(seq) => seq,
+                        Err(err) => {
+                            let _ = resp.send(format!("Failed to persist batch: {err}")).await;
+                            continue;
+                        }
+                    };
+
+                    {
+                        let mut guard = self.store.lock().unwrap();
+                        guard.store.add_batch(values.as_slice());
+                        guard.applied_seq = seq;
+                    }
+
+                    // Mirror the batch into the full-history segment tree and
+                    // retained price history.
+                    let leaves: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+                    self.tree.add_batch(&leaves);
+                    self.prices.extend_from_slice(&leaves);
+
+                    // Periodically flush a compact snapshot off the request path
+                    // so recovery does not need to replay the whole WAL.
+                    self.batches_since_snapshot += 1;
+                    if self.batches_since_snapshot >= self.snapshot_interval {
+                        self.batches_since_snapshot = 0;
+                        let data_dir = self.persistence.data_dir();
+                        let symbol = self.persistence.symbol();
+                        let label = symbol.clone();
+                        // Copy the live window on the blocking thread, not the
+                        // ingest path: only the Arc clone stays here. The seq is
+                        // read under the same lock as the snapshot so the two
+                        // always agree.
+                        let store = Arc::clone(&self.store);
+                        tokio::task::spawn_blocking(move || {
+                            let (snapshot, seq) = {
+                                let guard = store.lock().unwrap();
+                                (guard.store.snapshot(), guard.applied_seq)
+                            };
+                            if let Err(err) =
+                                persistence::flush_snapshot(data_dir, symbol, seq, snapshot)
+                            {
+                                eprintln!("Failed to flush snapshot for {label}: {err}");
+                            }
+                        });
+                    }
+
                     let _ = resp.send("Batch added successfully".to_string()).await;
                 }
 
                 ManagerCommand::GetStats { k, resp } => {
-                    let stats = self.store.get_stats(k);
+                    let stats = self.store.lock().unwrap().store.get_stats(k);
 
                     let response: Stats = stats
                         .map(|stats| {
-                            let avg = stats.sum / stats.count as f32;
-                            let var = (stats.sum_squares / stats.count as f32) - (avg * avg);
+                            let count = stats.count as f64;
+                            let var_pop = if stats.count > 0 { stats.m2 / count } else { 0.0 };
+                            let var_sample = if stats.count > 1 {
+                                stats.m2 / (count - 1.0)
+                            } else {
+                                0.0
+                            };
 
                             Stats {
                                 min: stats.min,
                                 max: stats.max,
                                 last: stats.last,
-                                avg,
-                                var,
+                                avg: stats.mean as f32,
+                                var_pop: var_pop as f32,
+                                var_sample: var_sample as f32,
+                                std_pop: var_pop.sqrt() as f32,
+                                std_sample: var_sample.sqrt() as f32,
                             }
                         })
                         .unwrap_or(Stats {
@@ -109,12 +366,68 @@ impl SymbolTask {
                             max: 0.0,
                             last: 0.0,
                             avg: 0.0,
-                            var: 0.0,
+                            var_pop: 0.0,
+                            var_sample: 0.0,
+                            std_pop: 0.0,
+                            std_sample: 0.0,
                         });
 
                     let _ = resp.send(response).await;
                     continue;
                 }
+
+                ManagerCommand::GetRangeStats { start, end, resp } => {
+                    let node = self.tree.query_range(start, end);
+
+                    let response = if node.count == 0 {
+                        RangeStats {
+                            min: 0.0,
+                            max: 0.0,
+                            avg: 0.0,
+                            var: 0.0,
+                            sum: 0.0,
+                            count: 0,
+                            last: 0.0,
+                        }
+                    } else {
+                        RangeStats {
+                            min: node.min,
+                            max: node.max,
+                            avg: node.mean,
+                            var: node.variance(),
+                            sum: node.sum,
+                            count: node.count,
+                            last: node.last,
+                        }
+                    };
+
+                    let _ = resp.send(response).await;
+                    continue;
+                }
+
+                ManagerCommand::GetPercentile {
+                    start,
+                    end,
+                    q,
+                    resp,
+                } => {
+                    // (Re)build the immutable merge-sort tree snapshot only when
+                    // new prices have arrived since the last build, then query
+                    // the cached tree.
+                    if self.percentile_tree.is_none()
+                        || self.percentile_tree_len != self.prices.len()
+                    {
+                        self.percentile_tree = Some(MergeSortTree::build(&self.prices));
+                        self.percentile_tree_len = self.prices.len();
+                    }
+                    let tree = self.percentile_tree.as_ref().unwrap();
+
+                    let value = tree.percentile(start, end, q).unwrap_or(0.0);
+                    let count = tree.count_le(start, end.min(tree.len()), f64::INFINITY);
+
+                    let _ = resp.send(Percentile { q, value, count }).await;
+                    continue;
+                }
             }
         }
     }
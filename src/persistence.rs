@@ -0,0 +1,232 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::datastore::StoreSnapshot;
+
+// PersistenceConfig controls where durable state lives and how often compact
+// snapshots are flushed. `snapshot_interval` is counted in accepted batches.
+#[derive(Clone, Debug)]
+pub struct PersistenceConfig {
+    pub data_dir: PathBuf,
+    pub snapshot_interval: u64,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        PersistenceConfig {
+            data_dir: PathBuf::from("./data"),
+            snapshot_interval: 1000,
+        }
+    }
+}
+
+// WalRecord is a single durably-logged batch, tagged with a monotonically
+// increasing sequence number so snapshots can mark how far they have consumed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub values: Vec<f32>,
+}
+
+// Snapshot pairs the compact store state with the highest sequence number it
+// already reflects; recovery replays only WAL records beyond `seq`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub seq: u64,
+    pub store: StoreSnapshot,
+}
+
+// RecoveredState is everything read back for one symbol at startup.
+pub struct RecoveredState {
+    pub snapshot: Option<Snapshot>,
+    pub records: Vec<WalRecord>,
+}
+
+// SymbolPersistence owns the append-only write-ahead log for a single symbol
+// and writes its periodic snapshots. The WAL handle is kept open for the task's
+// lifetime so the per-batch append is a plain write + fsync.
+pub struct SymbolPersistence {
+    data_dir: PathBuf,
+    symbol: String,
+    wal: File,
+    seq: u64,
+}
+
+impl SymbolPersistence {
+    // Open (creating if needed) the WAL for `symbol`, continuing its sequence
+    // from `next_seq` (the highest recovered sequence + 1, or 0 when fresh).
+    pub fn open(config: &PersistenceConfig, symbol: &str, next_seq: u64) -> std::io::Result<Self> {
+        if !is_valid_symbol(symbol) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid symbol name: {symbol:?}"),
+            ));
+        }
+
+        fs::create_dir_all(&config.data_dir)?;
+        let mut wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(wal_path(&config.data_dir, symbol))?;
+
+        // A crash mid-append can leave a torn record without its newline;
+        // terminate it so the next record starts on a line of its own.
+        let len = wal.metadata()?.len();
+        if len > 0 {
+            let mut last = [0u8; 1];
+            wal.seek(SeekFrom::Start(len - 1))?;
+            wal.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                wal.write_all(b"\n")?;
+            }
+        }
+
+        Ok(SymbolPersistence {
+            data_dir: config.data_dir.clone(),
+            symbol: symbol.to_string(),
+            wal,
+            seq: next_seq,
+        })
+    }
+
+    // Append a batch to the WAL and fsync before returning, so the caller only
+    // acknowledges a batch that is durably on disk. Returns the assigned seq.
+    pub fn append_batch(&mut self, values: &[f32]) -> std::io::Result<u64> {
+        let seq = self.seq;
+        let record = WalRecord {
+            seq,
+            values: values.to_vec(),
+        };
+        let mut line = serde_json::to_string(&record).map_err(std::io::Error::other)?;
+        line.push('\n');
+        self.wal.write_all(line.as_bytes())?;
+        self.wal.sync_all()?;
+
+        self.seq += 1;
+        Ok(seq)
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir.clone()
+    }
+
+    pub fn symbol(&self) -> String {
+        self.symbol.clone()
+    }
+
+    // Sequence number of the most recently appended batch.
+    pub fn current_seq(&self) -> u64 {
+        self.seq.saturating_sub(1)
+    }
+
+    // Atomically write a snapshot covering every batch appended so far.
+    pub fn write_snapshot(&self, store: StoreSnapshot) -> std::io::Result<()> {
+        flush_snapshot(self.data_dir.clone(), self.symbol.clone(), self.current_seq(), store)
+    }
+}
+
+// Atomically persist a snapshot: write to a temp file and rename over the
+// target so a crash never leaves a torn snapshot behind. Standalone so it can
+// run on a blocking thread off the ingest path.
+pub fn flush_snapshot(
+    data_dir: PathBuf,
+    symbol: String,
+    seq: u64,
+    store: StoreSnapshot,
+) -> std::io::Result<()> {
+    let snapshot = Snapshot { seq, store };
+    let bytes = serde_json::to_vec(&snapshot).map_err(std::io::Error::other)?;
+
+    let final_path = snapshot_path(&data_dir, &symbol);
+    let tmp_path = final_path.with_extension("snap.tmp");
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, &final_path)?;
+    Ok(())
+}
+
+// Symbol names become filenames under the data directory, so reject anything
+// that could escape it or collide with path syntax. Only plain identifier-ish
+// characters are allowed.
+pub fn is_valid_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && symbol.len() <= 64
+        && symbol != "."
+        && symbol != ".."
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+// Enumerate the symbols that have persisted state under the data directory.
+pub fn list_symbols(config: &PersistenceConfig) -> std::io::Result<Vec<String>> {
+    let mut symbols = Vec::new();
+    if !config.data_dir.exists() {
+        return Ok(symbols);
+    }
+
+    for entry in fs::read_dir(&config.data_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("wal") {
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                symbols.push(stem.to_string());
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+// Load the latest snapshot (if any) plus the full WAL for `symbol`.
+pub fn recover(config: &PersistenceConfig, symbol: &str) -> std::io::Result<RecoveredState> {
+    let snapshot = read_snapshot(config, symbol)?;
+    let records = read_wal(config, symbol)?;
+    Ok(RecoveredState { snapshot, records })
+}
+
+fn read_snapshot(config: &PersistenceConfig, symbol: &str) -> std::io::Result<Option<Snapshot>> {
+    let path = snapshot_path(&config.data_dir, symbol);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+    let snapshot = serde_json::from_slice(&bytes).map_err(std::io::Error::other)?;
+    Ok(Some(snapshot))
+}
+
+fn read_wal(config: &PersistenceConfig, symbol: &str) -> std::io::Result<Vec<WalRecord>> {
+    let path = wal_path(&config.data_dir, symbol);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut records = Vec::new();
+    let reader = BufReader::new(File::open(path)?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A torn record (from a crash mid-append) is dropped rather than
+        // aborting recovery; records appended after it are still read.
+        match serde_json::from_str::<WalRecord>(&line) {
+            Ok(record) => records.push(record),
+            Err(_) => continue,
+        }
+    }
+    Ok(records)
+}
+
+fn wal_path(data_dir: &Path, symbol: &str) -> PathBuf {
+    data_dir.join(format!("{}.wal", symbol))
+}
+
+fn snapshot_path(data_dir: &Path, symbol: &str) -> PathBuf {
+    data_dir.join(format!("{}.snap", symbol))
+}
@@ -0,0 +1,89 @@
+// RingBuffer is a fixed-capacity circular buffer of prices. The backing storage
+// is allocated once at construction and never reallocates, so the per-batch hot
+// path does zero allocation. Once full, a `push` overwrites and returns the
+// oldest value, giving the sliding-window eviction the interval stores rely on.
+#[derive(Debug)]
+pub struct RingBuffer {
+    data: Box<[f32]>,
+    capacity: usize,
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        RingBuffer {
+            data: vec![0.0; capacity].into_boxed_slice(),
+            capacity,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    // Push a value. While the buffer is filling returns `None`; once at capacity
+    // the oldest value is overwritten and returned as the evicted element.
+    pub fn push(&mut self, value: f32) -> Option<f32> {
+        if self.len < self.capacity {
+            let idx = (self.head + self.len) % self.capacity;
+            self.data[idx] = value;
+            self.len += 1;
+            None
+        } else {
+            let evicted = self.data[self.head];
+            self.data[self.head] = value;
+            self.head = (self.head + 1) % self.capacity;
+            Some(evicted)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Current contents in age order, oldest first. Used to snapshot the live
+    // window so it can be restored without replaying the full price history.
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut out = Vec::with_capacity(self.len);
+        for i in 0..self.len {
+            out.push(self.data[(self.head + i) % self.capacity]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fills_without_eviction() {
+        let mut buf = RingBuffer::with_capacity(3);
+        assert_eq!(buf.push(1.0), None);
+        assert_eq!(buf.push(2.0), None);
+        assert_eq!(buf.push(3.0), None);
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn test_evicts_oldest_when_full() {
+        let mut buf = RingBuffer::with_capacity(3);
+        buf.push(1.0);
+        buf.push(2.0);
+        buf.push(3.0);
+
+        // Now at capacity: each push evicts the oldest value in order.
+        assert_eq!(buf.push(4.0), Some(1.0));
+        assert_eq!(buf.push(5.0), Some(2.0));
+        assert_eq!(buf.push(6.0), Some(3.0));
+        assert_eq!(buf.len(), 3);
+        assert_eq!(buf.capacity(), 3);
+    }
+}
@@ -1,23 +1,39 @@
 use core::f64;
 
+// A monoid describes the reducer the tree is built over: an associative
+// `combine` with an `identity` element. Parameterizing the tree over this
+// trait keeps the tree machinery (capacity, point updates, range queries)
+// free of any knowledge of the aggregate it is computing.
+pub trait Monoid {
+    type Item: Clone;
+
+    fn identity() -> Self::Item;
+    fn combine(a: &Self::Item, b: &Self::Item) -> Self::Item;
+}
+
 // NodeData holding row ingradients for statistics calculation
 #[derive(Clone, Copy, Debug)]
 pub struct NodeData {
     pub min: f64,
     pub max: f64,
     pub sum: f64,
-    pub sum_squares: f64,
+    // Running mean and M2 (sum of squared deviations from the mean) so that
+    // variance is computed via Chan's parallel combination rather than the
+    // cancellation-prone `sum_squares / count - avg^2`.
+    pub mean: f64,
+    pub m2: f64,
     pub count: i64,
     pub last: f64,
 }
 
 impl NodeData {
-    fn new(value: f64) -> Self {
+    pub fn new(value: f64) -> Self {
         NodeData {
             min: value,
             max: value,
             sum: value,
-            sum_squares: value * value,
+            mean: value,
+            m2: 0.0,
             count: 1,
             last: value,
         }
@@ -30,41 +46,79 @@ impl NodeData {
         if right.count == 0 {
             return *left;
         }
+        // Chan's parallel variance combination over the two partitions.
+        let n_a = left.count as f64;
+        let n_b = right.count as f64;
+        let n = n_a + n_b;
+        let delta = right.mean - left.mean;
         NodeData {
             min: left.min.min(right.min),
             max: left.max.max(right.max),
             sum: left.sum + right.sum,
-            sum_squares: left.sum_squares + right.sum_squares,
+            mean: left.mean + delta * n_b / n,
+            m2: left.m2 + right.m2 + delta * delta * n_a * n_b / n,
             count: left.count + right.count,
             last: right.last,
         }
     }
 
+    // Population variance (M2 / count).
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    // Sample variance (M2 / (count - 1)).
+    pub fn sample_variance(&self) -> f64 {
+        if self.count > 1 {
+            self.m2 / (self.count - 1) as f64
+        } else {
+            0.0
+        }
+    }
+
     fn zero() -> Self {
         NodeData {
             min: f64::INFINITY,
             max: f64::NEG_INFINITY,
             sum: 0.0,
-            sum_squares: 0.0,
+            mean: 0.0,
+            m2: 0.0,
             count: 0,
             last: 0.0,
         }
     }
 }
 
-// Core strcture that holds the segment tree
-pub struct SegmentTree {
-    pub tree: Vec<NodeData>,
+// The default aggregate: NodeData is its own monoid, combining by merge.
+impl Monoid for NodeData {
+    type Item = NodeData;
+
+    fn identity() -> NodeData {
+        NodeData::zero()
+    }
+
+    fn combine(a: &NodeData, b: &NodeData) -> NodeData {
+        NodeData::merge(a, b)
+    }
+}
+
+// Core strcture that holds the segment tree over an arbitrary monoid `M`.
+pub struct SegmentTree<M: Monoid> {
+    pub tree: Vec<M::Item>,
     pub size: usize,
     pub current_position: usize,
 }
 
-impl SegmentTree {
+impl<M: Monoid> SegmentTree<M> {
     pub fn new() -> Self {
         let initial_size = 1024;
         let tree_size = initial_size * 2;
         SegmentTree {
-            tree: vec![NodeData::zero(); tree_size],
+            tree: vec![M::identity(); tree_size],
             size: initial_size,
             current_position: 0,
         }
@@ -86,11 +140,11 @@ impl SegmentTree {
         // Save leaf nodes
         let mut leaves = Vec::with_capacity(self.current_position);
         for i in 0..self.current_position {
-            leaves.push(self.tree[self.size + i]);
+            leaves.push(self.tree[self.size + i].clone());
         }
 
         // Resize the tree
-        self.tree = vec![NodeData::zero(); new_size * 2];
+        self.tree = vec![M::identity(); new_size * 2];
         self.size = new_size;
 
         // Restore leaf nodes
@@ -100,21 +154,23 @@ impl SegmentTree {
 
         // Rebuild internal nodes from leaves up
         for i in (1..self.size).rev() {
-            self.tree[i] = NodeData::merge(&self.tree[i * 2], &self.tree[i * 2 + 1]);
+            self.tree[i] = M::combine(&self.tree[i * 2], &self.tree[i * 2 + 1]);
         }
     }
 
-    fn update(&mut self, pos: usize, value: f64) {
+    // Point update: overwrite the leaf at `pos` with `item` and fold the
+    // change back up to the root.
+    fn update(&mut self, pos: usize, item: M::Item) {
         let mut node = pos + self.size;
-        self.tree[node] = NodeData::new(value);
+        self.tree[node] = item;
 
         while node > 1 {
             node /= 2;
-            self.tree[node] = NodeData::merge(&self.tree[node * 2], &self.tree[node * 2 + 1]);
+            self.tree[node] = M::combine(&self.tree[node * 2], &self.tree[node * 2 + 1]);
         }
     }
 
-    pub fn query_range(&self, start: usize, end: usize) -> NodeData {
+    pub fn query_range(&self, start: usize, end: usize) -> M::Item {
         self.query_internal(1, 0, self.size, start, end)
     }
 
@@ -125,32 +181,201 @@ impl SegmentTree {
         right: usize,
         start: usize,
         end: usize,
-    ) -> NodeData {
+    ) -> M::Item {
         if end <= left || right <= start {
-            return NodeData::zero();
+            return M::identity();
         }
 
         if start <= left && right <= end {
-            return self.tree[node];
+            return self.tree[node].clone();
         }
 
         let mid = (left + right) / 2;
         let left_result = self.query_internal(node * 2, left, mid, start, end);
         let right_result = self.query_internal(node * 2 + 1, mid, right, start, end);
 
-        NodeData::merge(&left_result, &right_result)
+        M::combine(&left_result, &right_result)
     }
 
-    pub fn add_batch(&mut self, values: &[f64]) {
-        self.ensure_capacity(self.current_position + values.len());
+    // Append already-reduced leaf items to the tree, growing as needed.
+    pub fn append(&mut self, items: &[M::Item]) {
+        self.ensure_capacity(self.current_position + items.len());
 
-        for &value in values {
-            self.update(self.current_position, value);
+        for item in items {
+            let pos = self.current_position;
+            self.update(pos, item.clone());
             self.current_position += 1;
         }
     }
 }
 
+impl<M: Monoid> Default for SegmentTree<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Build a tree directly from a sequence of leaf items, sizing the backing
+// array once and rebuilding all internal nodes bottom-up in O(n).
+impl<M: Monoid> FromIterator<M::Item> for SegmentTree<M> {
+    fn from_iter<I: IntoIterator<Item = M::Item>>(iter: I) -> Self {
+        let leaves: Vec<M::Item> = iter.into_iter().collect();
+
+        let mut size = 1024;
+        while size < leaves.len() {
+            size *= 2;
+        }
+
+        let mut tree = vec![M::identity(); size * 2];
+        let current_position = leaves.len();
+        for (i, leaf) in leaves.into_iter().enumerate() {
+            tree[size + i] = leaf;
+        }
+        for i in (1..size).rev() {
+            tree[i] = M::combine(&tree[i * 2], &tree[i * 2 + 1]);
+        }
+
+        SegmentTree {
+            tree,
+            size,
+            current_position,
+        }
+    }
+}
+
+// Convenience layer for the statistics aggregate: accept raw price points and
+// lift each into a NodeData leaf before handing them to the generic machinery.
+impl SegmentTree<NodeData> {
+    pub fn add_batch(&mut self, values: &[f64]) {
+        let leaves: Vec<NodeData> = values.iter().map(|&v| NodeData::new(v)).collect();
+        self.append(&leaves);
+    }
+}
+
+// Merge two already-sorted slices into a fresh sorted vector.
+fn merge_sorted(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] <= b[j] {
+            out.push(a[i]);
+            i += 1;
+        } else {
+            out.push(b[j]);
+            j += 1;
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+// A merge-sort tree: every internal node stores the sorted vector of all leaf
+// values in its range (O(n log n) memory), enabling order-statistic queries the
+// monoid SegmentTree cannot answer.
+//
+// The structure is an immutable snapshot: it is built bottom-up from a fixed
+// slice of leaves and has no point-update path. When new batches arrive the
+// tree must be rebuilt from the extended price sequence rather than mutated in
+// place, which callers handle by building on demand from the retained history.
+pub struct MergeSortTree {
+    nodes: Vec<Vec<f64>>,
+    size: usize,
+    n: usize,
+}
+
+impl MergeSortTree {
+    pub fn build(values: &[f64]) -> Self {
+        let n = values.len();
+        let mut size = 1;
+        while size < n.max(1) {
+            size *= 2;
+        }
+
+        let mut nodes = vec![Vec::new(); size * 2];
+        for (i, &v) in values.iter().enumerate() {
+            nodes[size + i] = vec![v];
+        }
+        for i in (1..size).rev() {
+            nodes[i] = merge_sorted(&nodes[i * 2], &nodes[i * 2 + 1]);
+        }
+
+        MergeSortTree { nodes, size, n }
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    // Count values in the half-open range `[start, end)` that are `<= x`,
+    // decomposing the range into O(log n) canonical nodes and binary-searching
+    // each node's sorted vector — O(log^2 n) overall.
+    pub fn count_le(&self, start: usize, end: usize, x: f64) -> usize {
+        self.count_internal(1, 0, self.size, start, end, x)
+    }
+
+    fn count_internal(
+        &self,
+        node: usize,
+        left: usize,
+        right: usize,
+        start: usize,
+        end: usize,
+        x: f64,
+    ) -> usize {
+        if end <= left || right <= start {
+            return 0;
+        }
+
+        if start <= left && right <= end {
+            return self.nodes[node].partition_point(|&v| v <= x);
+        }
+
+        let mid = (left + right) / 2;
+        self.count_internal(node * 2, left, mid, start, end, x)
+            + self.count_internal(node * 2 + 1, mid, right, start, end, x)
+    }
+
+    // The q-th quantile (q in [0, 1]) of the values in `[start, end)`: the
+    // smallest value whose `<=` count reaches `ceil(q * total)`. Returns `None`
+    // for an empty range. The value domain is the globally sorted leaf set
+    // (the root node), binary-searched for the threshold.
+    pub fn percentile(&self, start: usize, end: usize, q: f64) -> Option<f64> {
+        let end = end.min(self.n);
+        if start >= end {
+            return None;
+        }
+
+        let total = self.count_le(start, end, f64::INFINITY);
+        if total == 0 {
+            return None;
+        }
+
+        let q = q.clamp(0.0, 1.0);
+        let mut target = (q * total as f64).ceil() as usize;
+        target = target.clamp(1, total);
+
+        // Binary search the sorted value domain for the smallest value whose
+        // count reaches `target`.
+        let domain = &self.nodes[1];
+        let (mut lo, mut hi) = (0usize, domain.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.count_le(start, end, domain[mid]) >= target {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        domain.get(lo).copied()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,7 +432,10 @@ mod tests {
         assert_float_eq(result.min, 1.0);
         assert_float_eq(result.max, 5.0);
         assert_float_eq(result.sum, 15.0);
-        assert_float_eq(result.sum_squares, 55.0);
+        assert_float_eq(result.mean, 3.0);
+        assert_float_eq(result.m2, 10.0);
+        assert_float_eq(result.variance(), 2.0);
+        assert_float_eq(result.sample_variance(), 2.5);
         assert_eq!(result.count, 5);
         assert_float_eq(result.last, 5.0);
 
@@ -240,7 +468,8 @@ mod tests {
         assert_float_eq(result.min, 1.0);
         assert_float_eq(result.max, 5.0);
         assert_float_eq(result.sum, 15.0);
-        assert_float_eq(result.sum_squares, 55.0);
+        assert_float_eq(result.mean, 3.0);
+        assert_float_eq(result.m2, 10.0);
         assert_eq!(result.count, 5);
         assert_float_eq(result.last, 5.0);
 
@@ -260,7 +489,7 @@ mod tests {
     }
     #[test]
     fn test_infinity_handling() {
-        let mut tree = SegmentTree::new();
+        let mut tree = SegmentTree::<NodeData>::new();
 
         // Empty tree should return infinity values
         let result = tree.query_range(0, 1);
@@ -297,7 +526,7 @@ mod tests {
 
         let result = tree.query_range(0, 3);
         assert!(result.sum.is_nan());
-        assert!(result.sum_squares.is_nan());
+        assert!(result.mean.is_nan());
         assert_eq!(result.count, 3);
     }
 
@@ -312,7 +541,67 @@ mod tests {
         assert_float_eq(result.min, 1.0);
         assert_float_eq(result.max, 1.0);
         assert_float_eq(result.sum, 18_000.0);
-        assert_float_eq(result.sum_squares, 18_000.0);
+        assert_float_eq(result.m2, 0.0);
         assert_eq!(result.count, 18_000);
     }
+
+    #[test]
+    fn test_from_iter_builds_bottom_up() {
+        let leaves: Vec<NodeData> = (1..=5).map(|x| NodeData::new(x as f64)).collect();
+        let tree: SegmentTree<NodeData> = leaves.into_iter().collect();
+
+        let result = tree.query_range(0, 5);
+        assert_float_eq(result.min, 1.0);
+        assert_float_eq(result.max, 5.0);
+        assert_float_eq(result.sum, 15.0);
+        assert_eq!(result.count, 5);
+        assert_float_eq(result.last, 5.0);
+    }
+
+    // A second monoid over the same engine: plain f64 product.
+    struct Product;
+
+    impl Monoid for Product {
+        type Item = f64;
+
+        fn identity() -> f64 {
+            1.0
+        }
+
+        fn combine(a: &f64, b: &f64) -> f64 {
+            a * b
+        }
+    }
+
+    #[test]
+    fn test_merge_sort_tree_count_and_percentile() {
+        let values = vec![5.0, 1.0, 3.0, 2.0, 4.0, 6.0, 0.0, 7.0];
+        let tree = MergeSortTree::build(&values);
+
+        // count_le over the full range is just the sorted position.
+        assert_eq!(tree.count_le(0, 8, 3.0), 4); // 0,1,2,3
+        assert_eq!(tree.count_le(0, 8, -1.0), 0);
+        assert_eq!(tree.count_le(0, 8, 100.0), 8);
+
+        // count_le over a sub-range.
+        assert_eq!(tree.count_le(0, 4, 3.0), 3); // {5,1,3,2} -> 1,2,3
+
+        // median (p50) of 0..=7 is the 4th smallest value = 3.0.
+        assert_float_eq(tree.percentile(0, 8, 0.5).unwrap(), 3.0);
+        // p100 is the max.
+        assert_float_eq(tree.percentile(0, 8, 1.0).unwrap(), 7.0);
+        // smallest value for a tiny quantile.
+        assert_float_eq(tree.percentile(0, 8, 0.01).unwrap(), 0.0);
+
+        // empty range yields None.
+        assert!(tree.percentile(3, 3, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_alternate_monoid_product() {
+        let mut tree: SegmentTree<Product> = SegmentTree::new();
+        tree.append(&[1.0, 2.0, 3.0, 4.0]);
+        assert_float_eq(tree.query_range(0, 4), 24.0);
+        assert_float_eq(tree.query_range(1, 3), 6.0);
+    }
 }
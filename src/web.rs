@@ -2,7 +2,7 @@ use axum::extract::{Json, Query};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
-use crate::manager::{ManagerCommand, Stats, Symbol};
+use crate::manager::{ManagerCommand, Percentile, RangeStats, Stats, Symbol};
 
 // AddBatchRequest is a struct that represents the request body for the add_batch endpoint.
 #[derive(Deserialize)]
@@ -24,6 +24,23 @@ pub struct StatsRequest {
     pub k: u32,
 }
 
+// RangeStatsRequest is a struct that represents the request query parameters for the range_stats endpoint.
+#[derive(Deserialize)]
+pub struct RangeStatsRequest {
+    pub symbol: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// PercentileRequest is a struct that represents the request query parameters for the percentile endpoint.
+#[derive(Deserialize)]
+pub struct PercentileRequest {
+    pub symbol: String,
+    pub start: usize,
+    pub end: usize,
+    pub q: f64,
+}
+
 // RouterHandle is a struct that holds the manager_tx sender and forwards requests to the manager.
 #[derive(Clone)]
 pub struct RouterHandle {
@@ -67,7 +84,53 @@ impl RouterHandle {
             max: 0.0,
             last: 0.0,
             avg: 0.0,
+            var_pop: 0.0,
+            var_sample: 0.0,
+            std_pop: 0.0,
+            std_sample: 0.0,
+        }))
+    }
+
+    pub async fn handle_get_range_stats(
+        self,
+        Query(params): Query<RangeStatsRequest>,
+    ) -> Json<RangeStats> {
+        let (resp_tx, mut resp_rx) = mpsc::channel(1);
+        let command = ManagerCommand::GetRangeStats {
+            start: params.start,
+            end: params.end,
+            resp: resp_tx,
+        };
+        let sym = Symbol(params.symbol);
+        let _ = self.manager_tx.send((sym, command)).await;
+        Json(resp_rx.recv().await.unwrap_or(RangeStats {
+            min: 0.0,
+            max: 0.0,
+            avg: 0.0,
             var: 0.0,
+            sum: 0.0,
+            count: 0,
+            last: 0.0,
+        }))
+    }
+
+    pub async fn handle_get_percentile(
+        self,
+        Query(params): Query<PercentileRequest>,
+    ) -> Json<Percentile> {
+        let (resp_tx, mut resp_rx) = mpsc::channel(1);
+        let command = ManagerCommand::GetPercentile {
+            start: params.start,
+            end: params.end,
+            q: params.q,
+            resp: resp_tx,
+        };
+        let sym = Symbol(params.symbol);
+        let _ = self.manager_tx.send((sym, command)).await;
+        Json(resp_rx.recv().await.unwrap_or(Percentile {
+            q: params.q,
+            value: 0.0,
+            count: 0,
         }))
     }
 }